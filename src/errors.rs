@@ -0,0 +1,61 @@
+/*
+   Copyright 2017 Takashi Ogura
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+ */
+use std::error::Error;
+use std::fmt::{self, Display};
+
+/// Error raised while operating on a `Joint` or the `Node` tree it is
+/// attached to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JointError {
+    /// A `Mimic` link was declared between two joints but the child's
+    /// `Mimic` instance could not be found when propagating a position.
+    MimicError {
+        from: String,
+        to: String,
+        message: String,
+    },
+    /// `reparent` would have linked `to` as an ancestor of `from` while
+    /// `to` is already `from` or a descendant of `from`, which would
+    /// disconnect part of the tree from its own root. `set_parent` refuses
+    /// the same move with `tree_utils::CyclicTreeError`, not this variant.
+    Cyclic { from: String, to: String },
+}
+
+impl Display for JointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JointError::MimicError {
+                ref from,
+                ref to,
+                ref message,
+            } => write!(f, "mimic error: {} -> {}: {}", from, to, message),
+            JointError::Cyclic { ref from, ref to } => write!(
+                f,
+                "cyclic error: {} -> {} would create a cycle",
+                from, to
+            ),
+        }
+    }
+}
+
+impl Error for JointError {
+    fn description(&self) -> &str {
+        match *self {
+            JointError::MimicError { .. } => "mimic error",
+            JointError::Cyclic { .. } => "cyclic error",
+        }
+    }
+}
@@ -14,26 +14,26 @@
    limitations under the License.
  */
 use na::{Isometry3, Real, Translation3, UnitQuaternion};
-use std::cell::{Ref, RefCell};
 use std::fmt::{self, Display};
 use std::ops::Deref;
-use std::rc::{Rc, Weak};
 
 use errors::*;
-use iterator::*;
 use joint::*;
 use link::*;
+use tree_utils::{TreeNode, TreeNodeImpl, TreeNodeRef, WeakTreeNode};
 
-type WeakNode<T> = Weak<RefCell<NodeImpl<T>>>;
+type WeakNode<T> = WeakTreeNode<NodeImpl<T>>;
 
 #[derive(Debug)]
-/// Node for joint tree struct
+/// Data carried by each node of the joint tree.
+///
+/// The parent/children topology itself lives in `tree_utils::TreeNode`;
+/// `Node<T>` is `TreeNode<NodeImpl<T>>`, so `NodeImpl` only needs to hold
+/// what is specific to a joint.
 pub struct NodeImpl<T>
 where
     T: Real,
 {
-    pub parent: Option<WeakNode<T>>,
-    pub children: Vec<Node<T>>,
     pub joint: Joint<T>,
     pub mimic_parent: Option<WeakNode<T>>,
     pub mimic_children: Vec<Node<T>>,
@@ -44,96 +44,51 @@ where
 /// Parts of `Chain`
 ///
 /// It contains joint, joint (transform), and parent/children.
-#[derive(Debug)]
-pub struct Node<T: Real>(Rc<RefCell<NodeImpl<T>>>);
+pub type Node<T> = TreeNode<NodeImpl<T>>;
+
+/// `Node::iter_ancestors()`'s iterator type; kept under its old name so
+/// code built against the pre-`tree_utils` `Node<T>` layout still resolves.
+pub type Ancestors<T> = tree_utils::Ancestors<NodeImpl<T>>;
+/// `Node::iter_descendants()`'s iterator type; kept under its old name so
+/// code built against the pre-`tree_utils` `Node<T>` layout still resolves.
+pub type Descendants<T> = tree_utils::Descendants<NodeImpl<T>>;
 
 impl<T> Node<T>
 where
     T: Real,
 {
-    pub(crate) fn from_rc(rc: Rc<RefCell<NodeImpl<T>>>) -> Self {
-        Node(rc)
-    }
-
     pub fn new(joint: Joint<T>) -> Self {
-        Node::<T>(Rc::new(RefCell::new(NodeImpl {
-            parent: None,
-            children: Vec::new(),
+        TreeNode::new(NodeImpl {
             joint,
             mimic_parent: None,
             mimic_children: Vec::new(),
             mimic: None,
             child_link: None,
-        })))
+        })
     }
 
     pub fn joint(&self) -> JointRefGuard<T> {
         JointRefGuard {
-            guard: self.0.borrow(),
+            guard: self.borrow(),
         }
     }
 
     pub fn parent(&self) -> ParentRefGuard<T> {
         ParentRefGuard {
-            guard: self.0.borrow(),
+            guard: self.borrow(),
         }
     }
 
     pub fn children(&self) -> ChildrenRefGuard<T> {
         ChildrenRefGuard {
-            guard: self.0.borrow(),
+            guard: self.borrow(),
         }
     }
 
-    /// iter from the end to root, it contains nodes[id] itself
-    #[inline]
-    pub fn iter_ancestors(&self) -> Ancestors<T> {
-        Ancestors::new(Some(self.clone()))
-    }
-    /// iter to the end, it contains nodes[id] itself
-    #[inline]
-    pub fn iter_descendants(&self) -> Descendants<T> {
-        Descendants::new(vec![self.clone()])
-    }
-
-    /// Set parent and child relations at same time
-    pub fn set_parent(&self, parent: &Node<T>) {
-        self.0.borrow_mut().parent = Some(Rc::downgrade(&parent.0));
-        parent.0.borrow_mut().children.push(self.clone());
-    }
-
-    /// # Examples
-    ///
-    /// ```
-    /// use k::*;
-    ///
-    /// let l0 = k::JointBuilder::<f32>::new().into_node();
-    /// let l1 = k::JointBuilder::new().into_node();
-    /// l1.set_parent(&l0);
-    /// assert!(l0.is_root());
-    /// assert!(!l1.is_root());
-    /// ```
-    pub fn is_root(&self) -> bool {
-        self.0.borrow().parent.is_none()
-    }
-
-    /// # Examples
-    ///
-    /// ```
-    /// let l0 = k::JointBuilder::<f64>::new().into_node();
-    /// let l1 = k::JointBuilder::new().into_node();
-    /// l1.set_parent(&l0);
-    /// assert!(!l0.is_end());
-    /// assert!(l1.is_end());
-    /// ```
-    pub fn is_end(&self) -> bool {
-        self.0.borrow().children.is_empty()
-    }
-
     /// Set the offset transform of the joint
     #[inline]
     pub fn set_offset(&self, trans: Isometry3<T>) {
-        self.0.borrow_mut().joint.set_offset(trans);
+        self.borrow_mut().data.joint.set_offset(trans);
     }
 
     /// Set the position (angle) of the joint
@@ -182,16 +137,19 @@ where
     /// assert_eq!(j1.joint().position().unwrap(), 1.6);
     /// ```
     pub fn set_position(&self, position: T) -> Result<(), JointError> {
-        let mut node = self.0.borrow_mut();
-        if node.mimic_parent.is_some() {
+        let mut node = self.borrow_mut();
+        if node.data.mimic_parent.is_some() {
             return Ok(());
         }
-        node.joint.set_position(position)?;
-        for child in &node.mimic_children {
-            let mut child_node = child.0.borrow_mut();
-            let mimic = child_node.mimic.clone();
+        node.data.joint.set_position(position)?;
+        for child in &node.data.mimic_children {
+            let mut child_node = child.borrow_mut();
+            let mimic = child_node.data.mimic.clone();
             match mimic {
-                Some(m) => child_node.joint.set_position(m.mimic_position(position))?,
+                Some(m) => child_node
+                    .data
+                    .joint
+                    .set_position(m.mimic_position(position))?,
                 None => {
                     let from = self.joint().name.to_owned();
                     let to = child.joint().name.to_owned();
@@ -212,15 +170,15 @@ where
     }
     #[inline]
     pub fn set_position_unchecked(&self, position: T) {
-        self.0.borrow_mut().joint.set_position_unchecked(position);
+        self.borrow_mut().data.joint.set_position_unchecked(position);
     }
 
     pub(crate) fn parent_world_transform(&self) -> Option<Isometry3<T>> {
-        match self.0.borrow().parent {
+        match self.borrow().parent {
             Some(ref parent) => {
-                let rc_parent = parent.upgrade().unwrap().clone();
-                let parent_obj = rc_parent.borrow();
-                parent_obj.joint.world_transform()
+                let parent_node = TreeNode::from_rc(parent.upgrade().unwrap());
+                let parent_obj = parent_node.borrow();
+                parent_obj.data.joint.world_transform()
             }
             None => Some(Isometry3::identity()),
         }
@@ -242,7 +200,7 @@ where
     ///     .translation(Translation3::new(0.0, 0.0, 1.0))
     ///     .joint_type(JointType::Linear{axis: Vector3::z_axis()})
     ///     .into_node();
-    /// l1.set_parent(&l0);
+    /// l1.set_parent(&l0).unwrap();
     /// let tree = Chain::<f64>::from_root(l0);
     /// tree.set_joint_positions(&vec![3.141592 * 0.5, 0.1]).unwrap();
     /// assert!(l1.world_transform().is_none());
@@ -255,50 +213,70 @@ where
     /// // _poses[1] is as same as l1.world_transform()
     #[inline]
     pub fn world_transform(&self) -> Option<Isometry3<T>> {
-        self.0.borrow().joint.world_transform()
+        self.borrow().data.joint.world_transform()
     }
 
     pub fn set_mimic_parent(&self, parent: &Node<T>, mimic: Mimic<T>) {
-        self.0.borrow_mut().mimic_parent = Some(Rc::downgrade(&parent.0));
-        parent.0.borrow_mut().mimic_children.push(self.clone());
-        self.0.borrow_mut().mimic = Some(mimic);
+        self.borrow_mut().data.mimic_parent = Some(parent.weak());
+        parent.borrow_mut().data.mimic_children.push(self.clone());
+        self.borrow_mut().data.mimic = Some(mimic);
     }
 
     pub fn set_child_link(&self, link: Option<Link<T>>) {
-        self.0.borrow_mut().child_link = link;
+        self.borrow_mut().data.child_link = link;
     }
 
     pub fn child_link(&self) -> ChildLinkRefGuard<T> {
         ChildLinkRefGuard {
-            guard: self.0.borrow(),
+            guard: self.borrow(),
         }
     }
-}
-
-impl<T> ::std::clone::Clone for Node<T>
-where
-    T: Real,
-{
-    fn clone(&self) -> Self {
-        Node::<T>(self.0.clone())
-    }
-}
 
-impl<T> PartialEq for Node<T>
-where
-    T: Real,
-{
-    fn eq(&self, other: &Node<T>) -> bool {
-        &*self.0 as *const RefCell<NodeImpl<T>> == &*other.0 as *const RefCell<NodeImpl<T>>
+    /// Detach this node from its current parent (if any) and attach it to
+    /// `new_parent` instead.
+    ///
+    /// Returns `Err(JointError::Cyclic)` without modifying the tree if
+    /// `new_parent` is `self` or a descendant of `self`, since linking it
+    /// as a parent would create a cycle that `Weak` parent links cannot
+    /// protect against on their own.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let l0 = k::JointBuilder::<f64>::new().into_node();
+    /// let l1 = k::JointBuilder::new().into_node();
+    /// let l2 = k::JointBuilder::new().into_node();
+    /// connect![l0 => l1 => l2];
+    ///
+    /// // moving l2 under l0 is fine
+    /// assert!(l2.reparent(&l0).is_ok());
+    /// assert!(l0.children().iter().any(|c| *c == l2));
+    ///
+    /// // moving l0 under its own descendant l1 would create a cycle
+    /// assert!(l0.reparent(&l1).is_err());
+    /// ```
+    pub fn reparent(&self, new_parent: &Node<T>) -> Result<(), JointError> {
+        if new_parent.iter_ancestors().any(|ancestor| ancestor == *self) {
+            return Err(JointError::Cyclic {
+                from: self.joint().name.to_owned(),
+                to: new_parent.joint().name.to_owned(),
+            });
+        }
+        self.detach();
+        self.set_parent(new_parent)
+            .expect("already checked for cycles above");
+        Ok(())
     }
 }
 
 impl<T: Real> Display for Node<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let inner = self.0.borrow();
-        inner.joint.fmt(f)?;
+        let inner = self.borrow();
+        inner.data.joint.fmt(f)?;
 
-        if let Some(l) = &inner.child_link {
+        if let Some(l) = &inner.data.child_link {
             write!(f, " => /{}/", l.name)?;
         }
         Ok(())
@@ -315,12 +293,12 @@ where
 }
 
 macro_rules! def_ref_guard {
-    ($guard_struct:ident, $target:ty, $member:ident) => {
+    ($guard_struct:ident, $target:ty, $($member:tt).+) => {
         pub struct $guard_struct<'a, T>
         where
             T: Real,
         {
-            guard: Ref<'a, NodeImpl<T>>,
+            guard: TreeNodeRef<'a, NodeImpl<T>>,
         }
 
         impl<'a, T> Deref for $guard_struct<'a, T>
@@ -329,14 +307,14 @@ macro_rules! def_ref_guard {
         {
             type Target = $target;
             fn deref(&self) -> &Self::Target {
-                &self.guard.$member
+                &self.guard.$($member).+
             }
         }
     };
 }
 
-def_ref_guard!(JointRefGuard, Joint<T>, joint);
-def_ref_guard!(ChildLinkRefGuard, Option<Link<T>>, child_link);
+def_ref_guard!(JointRefGuard, Joint<T>, data.joint);
+def_ref_guard!(ChildLinkRefGuard, Option<Link<T>>, data.child_link);
 def_ref_guard!(ChildrenRefGuard, Vec<Node<T>>, children);
 def_ref_guard!(ParentRefGuard, Option<WeakNode<T>>, parent);
 
@@ -448,10 +426,10 @@ where
 #[macro_export]
 macro_rules! connect {
     ($x:expr => $y:expr) => {
-        $y.set_parent(&$x);
+        $y.set_parent(&$x).unwrap();
     };
     ($x:expr => $y:expr => $($rest:tt)+) => {
-        $y.set_parent(&$x);
+        $y.set_parent(&$x).unwrap();
         connect!($y => $($rest)*);
     };
-}
\ No newline at end of file
+}
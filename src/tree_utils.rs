@@ -0,0 +1,369 @@
+/*
+   Copyright 2017 Takashi Ogura
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+ */
+//! Generic reference-counted tree, shared by any payload that needs
+//! parent/children topology. `Node<T>` is built on top of `TreeNode<D>`
+//! by instantiating it with `D = NodeImpl<T>`, so the traversal and
+//! parent-linking code below is written and tested once.
+//!
+//! By default the tree is built on `Rc<RefCell<_>>`, which is cheap but
+//! confines a `Chain` to a single thread. Enabling the `sync` Cargo
+//! feature swaps the backend for `Arc<RwLock<_>>` instead, so a `Chain`
+//! can be shared across threads (e.g. to evaluate candidate
+//! configurations in parallel). The accessor/mutator surface on
+//! `TreeNode` is identical either way; only the guard types returned by
+//! `borrow`/`borrow_mut` change.
+#[cfg(not(feature = "sync"))]
+mod backend {
+    use std::cell::{Ref, RefCell, RefMut};
+    use std::rc::{Rc, Weak};
+
+    pub type Ptr<T> = Rc<T>;
+    pub type WeakPtr<T> = Weak<T>;
+    pub type Cell<T> = RefCell<T>;
+    pub type Guard<'a, T> = Ref<'a, T>;
+    pub type GuardMut<'a, T> = RefMut<'a, T>;
+
+    pub fn new_cell<T>(value: T) -> Cell<T> {
+        RefCell::new(value)
+    }
+    pub fn read<T>(cell: &Cell<T>) -> Guard<T> {
+        cell.borrow()
+    }
+    pub fn write<T>(cell: &Cell<T>) -> GuardMut<T> {
+        cell.borrow_mut()
+    }
+}
+
+#[cfg(feature = "sync")]
+mod backend {
+    use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+
+    pub type Ptr<T> = Arc<T>;
+    pub type WeakPtr<T> = Weak<T>;
+    pub type Cell<T> = RwLock<T>;
+    pub type Guard<'a, T> = RwLockReadGuard<'a, T>;
+    pub type GuardMut<'a, T> = RwLockWriteGuard<'a, T>;
+
+    pub fn new_cell<T>(value: T) -> Cell<T> {
+        RwLock::new(value)
+    }
+    pub fn read<T>(cell: &Cell<T>) -> Guard<T> {
+        cell.read().expect("tree node lock poisoned")
+    }
+    pub fn write<T>(cell: &Cell<T>) -> GuardMut<T> {
+        cell.write().expect("tree node lock poisoned")
+    }
+}
+
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use self::backend::{new_cell, read, write, Cell, Ptr};
+
+/// A cell holding a node's topology + payload; `RefCell` or `RwLock`
+/// depending on the `sync` feature.
+type TreeCell<D> = Cell<TreeNodeImpl<D>>;
+/// A weak reference to a `TreeNode`'s underlying cell.
+pub type WeakTreeNode<D> = backend::WeakPtr<TreeCell<D>>;
+/// Read guard returned by `TreeNode::borrow`.
+pub(crate) type TreeNodeRef<'a, D> = backend::Guard<'a, TreeNodeImpl<D>>;
+/// Write guard returned by `TreeNode::borrow_mut`.
+pub(crate) type TreeNodeRefMut<'a, D> = backend::GuardMut<'a, TreeNodeImpl<D>>;
+
+/// Error returned by `TreeNode::set_parent` when `parent` is `self` or one
+/// of `self`'s own descendants, which would turn the tree into a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CyclicTreeError;
+
+impl fmt::Display for CyclicTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parent is self or one of self's descendants")
+    }
+}
+
+impl Error for CyclicTreeError {}
+
+#[derive(Debug)]
+/// Topology (parent/children links) plus the user-supplied payload `D`.
+pub struct TreeNodeImpl<D> {
+    pub parent: Option<WeakTreeNode<D>>,
+    pub children: Vec<TreeNode<D>>,
+    pub data: D,
+}
+
+/// A node in a reference-counted tree, parameterized over the payload `D`
+/// it carries.
+#[derive(Debug)]
+pub struct TreeNode<D>(Ptr<TreeCell<D>>);
+
+impl<D> TreeNode<D> {
+    pub(crate) fn from_rc(ptr: Ptr<TreeCell<D>>) -> Self {
+        TreeNode(ptr)
+    }
+
+    /// A weak reference to this node's cell, for use as a `parent` or
+    /// `mimic_parent` link.
+    pub(crate) fn weak(&self) -> WeakTreeNode<D> {
+        Ptr::downgrade(&self.0)
+    }
+
+    pub(crate) fn borrow(&self) -> TreeNodeRef<D> {
+        read(&self.0)
+    }
+
+    pub(crate) fn borrow_mut(&self) -> TreeNodeRefMut<D> {
+        write(&self.0)
+    }
+
+    /// Borrow this node's payload.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::tree_utils::TreeNode;
+    ///
+    /// let node = TreeNode::new("root");
+    /// assert_eq!(*node.data(), "root");
+    /// ```
+    pub fn data(&self) -> DataRef<D> {
+        DataRef {
+            guard: self.borrow(),
+        }
+    }
+
+    /// Mutably borrow this node's payload.
+    pub fn data_mut(&self) -> DataRefMut<D> {
+        DataRefMut {
+            guard: self.borrow_mut(),
+        }
+    }
+
+    pub fn new(data: D) -> Self {
+        TreeNode(Ptr::new(new_cell(TreeNodeImpl {
+            parent: None,
+            children: Vec::new(),
+            data,
+        })))
+    }
+
+    /// iter from the end to root, it contains nodes[id] itself
+    #[inline]
+    pub fn iter_ancestors(&self) -> Ancestors<D> {
+        Ancestors::new(Some(self.clone()))
+    }
+    /// iter to the end, it contains nodes[id] itself
+    #[inline]
+    pub fn iter_descendants(&self) -> Descendants<D> {
+        Descendants::new(vec![self.clone()])
+    }
+
+    /// `true` if `parent` is `self` or one of `self`'s own descendants,
+    /// i.e. linking `self` under `parent` would create a cycle.
+    fn would_cycle(&self, parent: &TreeNode<D>) -> bool {
+        parent.iter_ancestors().any(|ancestor| ancestor == *self)
+    }
+
+    /// Set parent and child relations at same time
+    ///
+    /// `TreeNode` carries an arbitrary payload `D`, not just kinematic
+    /// joints, so this works with any data.
+    ///
+    /// Returns `Err(CyclicTreeError)` without modifying the tree if
+    /// `parent` is `self` or one of `self`'s own descendants.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::tree_utils::TreeNode;
+    ///
+    /// let root = TreeNode::new("root");
+    /// let child = TreeNode::new("child");
+    /// child.set_parent(&root).unwrap();
+    /// assert!(root.is_root());
+    /// assert!(!child.is_root());
+    /// assert_eq!(*root.data(), "root");
+    /// assert_eq!(*child.data(), "child");
+    ///
+    /// assert!(root.set_parent(&child).is_err());
+    /// ```
+    pub fn set_parent(&self, parent: &TreeNode<D>) -> Result<(), CyclicTreeError> {
+        if self.would_cycle(parent) {
+            return Err(CyclicTreeError);
+        }
+        self.borrow_mut().parent = Some(parent.weak());
+        parent.borrow_mut().children.push(self.clone());
+        Ok(())
+    }
+
+    /// Remove `child` from this node's children, if present, and clear its
+    /// `parent` link.
+    pub fn remove_child(&self, child: &TreeNode<D>) {
+        self.borrow_mut().children.retain(|c| c != child);
+        let is_child_of_self = child
+            .borrow()
+            .parent
+            .clone()
+            .and_then(|weak| weak.upgrade())
+            .is_some_and(|parent| Ptr::ptr_eq(&parent, &self.0));
+        if is_child_of_self {
+            child.borrow_mut().parent = None;
+        }
+    }
+
+    /// Remove `self` from its parent's children and clear its own `parent`
+    /// link. A no-op if `self` is already a root.
+    pub fn detach(&self) {
+        // The borrow from `self.borrow()` must end before `remove_child`
+        // re-borrows `self` mutably, so bind the lookup to a `let` instead
+        // of using it directly as the `if let` scrutinee (which would
+        // extend the temporary's lifetime across the whole arm).
+        let parent = self
+            .borrow()
+            .parent
+            .clone()
+            .and_then(|weak| weak.upgrade())
+            .map(TreeNode::from_rc);
+        if let Some(parent) = parent {
+            parent.remove_child(self);
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.borrow().parent.is_none()
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// use k::tree_utils::TreeNode;
+    ///
+    /// let root = TreeNode::new("root");
+    /// let child = TreeNode::new("child");
+    /// child.set_parent(&root).unwrap();
+    /// assert!(!root.is_end());
+    /// assert!(child.is_end());
+    /// assert_eq!(*child.data(), "child");
+    /// ```
+    pub fn is_end(&self) -> bool {
+        self.borrow().children.is_empty()
+    }
+}
+
+impl<D> Clone for TreeNode<D> {
+    fn clone(&self) -> Self {
+        TreeNode(self.0.clone())
+    }
+}
+
+impl<D> PartialEq for TreeNode<D> {
+    fn eq(&self, other: &TreeNode<D>) -> bool {
+        Ptr::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A read guard projecting just the payload `D` out of a `TreeNode`.
+/// `TreeNode::borrow`, which returns the whole `TreeNodeImpl`, is
+/// crate-private, so this is how callers outside the crate read the data
+/// they stored.
+pub struct DataRef<'a, D: 'a> {
+    guard: TreeNodeRef<'a, D>,
+}
+
+impl<'a, D> Deref for DataRef<'a, D> {
+    type Target = D;
+    fn deref(&self) -> &D {
+        &self.guard.data
+    }
+}
+
+/// A write guard projecting just the payload `D` out of a `TreeNode`.
+pub struct DataRefMut<'a, D: 'a> {
+    guard: TreeNodeRefMut<'a, D>,
+}
+
+impl<'a, D> Deref for DataRefMut<'a, D> {
+    type Target = D;
+    fn deref(&self) -> &D {
+        &self.guard.data
+    }
+}
+
+impl<'a, D> DerefMut for DataRefMut<'a, D> {
+    fn deref_mut(&mut self) -> &mut D {
+        &mut self.guard.data
+    }
+}
+
+/// Iterates from a node up through its ancestors to the root; the starting
+/// node itself is included.
+pub struct Ancestors<D> {
+    parent: Option<TreeNode<D>>,
+}
+
+impl<D> Ancestors<D> {
+    pub(crate) fn new(parent: Option<TreeNode<D>>) -> Self {
+        Ancestors { parent }
+    }
+}
+
+impl<D> Iterator for Ancestors<D> {
+    type Item = TreeNode<D>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.parent.take();
+        self.parent = next
+            .as_ref()
+            .and_then(|node| node.borrow().parent.clone())
+            .and_then(|weak| weak.upgrade())
+            .map(TreeNode::from_rc);
+        next
+    }
+}
+
+/// Iterates depth-first through a node and all of its descendants; the
+/// starting node itself is included.
+pub struct Descendants<D> {
+    stack: Vec<TreeNode<D>>,
+}
+
+impl<D> Descendants<D> {
+    pub(crate) fn new(stack: Vec<TreeNode<D>>) -> Self {
+        Descendants { stack }
+    }
+}
+
+impl<D> Iterator for Descendants<D> {
+    type Item = TreeNode<D>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop();
+        if let Some(ref node) = node {
+            self.stack.extend(node.borrow().children.iter().cloned());
+        }
+        node
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::TreeNode;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn tree_node_is_send_and_sync_under_sync_feature() {
+        assert_send_sync::<TreeNode<u32>>();
+    }
+}